@@ -0,0 +1,295 @@
+use crate::CollectionError;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::raw::c_int;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+extern "C" {
+    fn flock(fd: c_int, operation: c_int) -> c_int;
+}
+
+const LOCK_SH: c_int = 1;
+const LOCK_EX: c_int = 2;
+const LOCK_NB: c_int = 4;
+const LOCK_UN: c_int = 8;
+
+/// Whether a [`crate::Collection`] blocks waiting for its advisory file
+/// lock or fails immediately if the lock is held elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Wait until the lock can be acquired.
+    Blocking,
+    /// Return `CollectionError::LockUnavailable` immediately if the lock is held.
+    Try,
+}
+
+/// RAII guard holding an `flock(2)` advisory lock on the backing file
+/// descriptor. The lock is released when the guard is dropped,
+/// including during a panic-driven unwind. Tracks the fd through a
+/// shared cell rather than a fixed number, so a `replace_all` that
+/// swaps in a new fd mid-guard still unlocks the right one.
+pub struct FileLockGuard {
+    fd: Arc<AtomicI32>,
+}
+
+impl Drop for FileLockGuard {
+    fn drop(&mut self) {
+        unsafe {
+            flock(self.fd.load(Ordering::SeqCst), LOCK_UN);
+        }
+    }
+}
+
+fn acquire_lock(fd: &Arc<AtomicI32>, operation: c_int, mode: LockMode) -> Result<FileLockGuard, CollectionError> {
+    let operation = match mode {
+        LockMode::Blocking => operation,
+        LockMode::Try => operation | LOCK_NB,
+    };
+    let ret = unsafe { flock(fd.load(Ordering::SeqCst), operation) };
+    if ret != 0 {
+        return Err(CollectionError::LockUnavailable);
+    }
+    Ok(FileLockGuard { fd: fd.clone() })
+}
+
+/// Storage abstraction a [`crate::Collection`] reads and writes fixed-width
+/// records through, independent of where the bytes actually live.
+pub trait Backend {
+    /// Guard type returned by `lock_shared`/`lock_exclusive`, released on drop.
+    type LockGuard;
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize>;
+    fn len(&self) -> std::io::Result<u64>;
+    /// Whether the backend currently holds no bytes.
+    fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+    fn set_len(&self, len: u64) -> std::io::Result<()>;
+    /// Read a single `\n`-terminated line starting at `offset`.
+    fn read_line_at(&self, offset: u64) -> std::io::Result<String>;
+
+    /// Atomically replace the whole backend's contents with `contents`,
+    /// used by full rewrites (`resize_db`/`compact`) instead of
+    /// truncating and rewriting slot by slot. A file-backed
+    /// implementation should write to a temp file and `rename` it over
+    /// the original so a crash mid-write can't leave the file truncated.
+    fn replace_all(&mut self, contents: &[u8]) -> std::io::Result<()>;
+
+    /// Flush previously written data to stable storage. A no-op unless
+    /// the backend was opened in durable mode.
+    fn sync(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Take a shared (read) lock, for `filter`/`find`/`by_uuid`.
+    fn lock_shared(&self) -> Result<Self::LockGuard, CollectionError>;
+    /// Take an exclusive (write) lock, for `insert`/`update`/`delete`/`resize_db`.
+    fn lock_exclusive(&self) -> Result<Self::LockGuard, CollectionError>;
+}
+
+/// The original on-disk backend: a plain `std::fs::File` with `flock(2)` advisory locking.
+pub struct FileBackend {
+    file: File,
+    fd: Arc<AtomicI32>,
+    fp: PathBuf,
+    lock_mode: LockMode,
+    /// When set, `sync` (called after every `insert`/`update`) `fsync`s
+    /// the file so a crash can't lose an acknowledged write.
+    durable: bool,
+}
+
+impl FileBackend {
+    pub fn open(fp: &Path, lock_mode: LockMode, durable: bool) -> std::io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .truncate(false)
+            .open(fp)?;
+        let fd = Arc::new(AtomicI32::new(file.as_raw_fd()));
+        Ok(FileBackend {
+            file,
+            fd,
+            fp: fp.to_path_buf(),
+            lock_mode,
+            durable,
+        })
+    }
+}
+
+impl Backend for FileBackend {
+    type LockGuard = FileLockGuard;
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        self.file.write_at(buf, offset)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn read_line_at(&self, offset: u64) -> std::io::Result<String> {
+        let mut reader = BufReader::new(&self.file);
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        Ok(line)
+    }
+
+    fn replace_all(&mut self, contents: &[u8]) -> std::io::Result<()> {
+        let dir = self.fp.parent().filter(|p| !p.as_os_str().is_empty());
+        let dir = dir.unwrap_or_else(|| Path::new("."));
+        let file_name = self
+            .fp
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("strufile.col");
+        let tmp_path = dir.join(format!(".{}.tmp-{}", file_name, Uuid::new_v4()));
+
+        let mut tmp = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        tmp.write_all(contents)?;
+        tmp.sync_all()?;
+        drop(tmp);
+
+        // Same-directory rename is atomic on the same filesystem: a
+        // crash here leaves either the old or the new file intact, never
+        // a half-written one.
+        fs::rename(&tmp_path, &self.fp)?;
+
+        let new_file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .truncate(false)
+            .open(&self.fp)?;
+        let new_fd = new_file.as_raw_fd();
+        // The caller is still inside the critical section its guard
+        // protects, but that guard's flock is on the fd we're about to
+        // close. Re-acquire it on the new fd and publish it through the
+        // shared cell before swapping the file in, so the guard keeps
+        // unlocking the fd that's actually live.
+        if unsafe { flock(new_fd, LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        self.fd.store(new_fd, Ordering::SeqCst);
+        self.file = new_file;
+        Ok(())
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        if self.durable {
+            self.file.sync_all()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn lock_shared(&self) -> Result<FileLockGuard, CollectionError> {
+        acquire_lock(&self.fd, LOCK_SH, self.lock_mode)
+    }
+
+    fn lock_exclusive(&self) -> Result<FileLockGuard, CollectionError> {
+        acquire_lock(&self.fd, LOCK_EX, self.lock_mode)
+    }
+}
+
+/// An in-memory backend, a `Vec<u8>` behind a lock, so the "In-Memory DB"
+/// mentioned on [`crate::Collection::new`] is real and tests no longer
+/// need to touch the filesystem. Locking here is only for thread-safety
+/// within one process; there is no cross-process concern to guard against.
+pub struct InMemoryBackend {
+    data: RwLock<Vec<u8>>,
+}
+
+impl InMemoryBackend {
+    pub fn new() -> Self {
+        InMemoryBackend {
+            data: RwLock::new(Vec::new()),
+        }
+    }
+}
+
+impl Default for InMemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for InMemoryBackend {
+    type LockGuard = ();
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let data = self.data.read().unwrap();
+        let offset = offset as usize;
+        if offset >= data.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(data.len() - offset);
+        buf[..n].copy_from_slice(&data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
+        let mut data = self.data.write().unwrap();
+        let offset = offset as usize;
+        let end = offset + buf.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.data.read().unwrap().len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.data.write().unwrap().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn read_line_at(&self, offset: u64) -> std::io::Result<String> {
+        let data = self.data.read().unwrap();
+        let offset = offset as usize;
+        if offset >= data.len() {
+            return Ok(String::new());
+        }
+        let rest = &data[offset..];
+        let line_end = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |i| i + 1);
+        Ok(String::from_utf8_lossy(&rest[..line_end]).into_owned())
+    }
+
+    fn replace_all(&mut self, contents: &[u8]) -> std::io::Result<()> {
+        // No partial-write/crash concern in memory; there's nothing to
+        // make atomic beyond the single assignment itself.
+        *self.data.get_mut().unwrap() = contents.to_vec();
+        Ok(())
+    }
+
+    fn lock_shared(&self) -> Result<(), CollectionError> {
+        Ok(())
+    }
+
+    fn lock_exclusive(&self) -> Result<(), CollectionError> {
+        Ok(())
+    }
+}
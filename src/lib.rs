@@ -1,123 +1,374 @@
+mod backend;
+
+pub use backend::{Backend, FileBackend, InMemoryBackend, LockMode};
+
 use chrono::Utc;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
-use std::os::unix::fs::FileExt;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
+/// Errors that can be raised by a [`Collection`].
+#[derive(Debug)]
+pub enum CollectionError {
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+    PrimaryKeyInUse,
+    Clash,
+    NotFound,
+    /// An advisory lock on the underlying file could not be obtained.
+    LockUnavailable,
+    /// A record's stored checksum didn't match its contents (integrity
+    /// mode only). `index` is the slot the corruption was found in.
+    CorruptRecord { uuid: Uuid, index: usize },
+}
+
+impl std::fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollectionError::Io(e) => write!(f, "io error: {}", e),
+            CollectionError::Serde(e) => write!(f, "serialisation error: {}", e),
+            CollectionError::PrimaryKeyInUse => write!(f, "primary key used"),
+            CollectionError::Clash => write!(f, "clash occurred"),
+            CollectionError::NotFound => write!(f, "no idx found"),
+            CollectionError::LockUnavailable => write!(f, "could not obtain file lock"),
+            CollectionError::CorruptRecord { uuid, index } => {
+                write!(f, "checksum mismatch for {} at slot {}", uuid, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
+
+impl From<std::io::Error> for CollectionError {
+    fn from(e: std::io::Error) -> Self {
+        CollectionError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CollectionError {
+    fn from(e: serde_json::Error) -> Self {
+        CollectionError::Serde(e)
+    }
+}
+
 /// Any struct that wants to be managed by a collection
 /// needs to satisfy these traits
 pub trait Document<T> {
     fn uuid(&self) -> Uuid;
     fn does_not_clash(&self, doc: &T) -> Result<(), &str>;
+    /// Field/value pairs to maintain secondary indexes on, so
+    /// `Collection::find_by` can seek straight to matching records
+    /// instead of scanning the whole file. Defaults to no indexes.
+    fn index_keys(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
 }
 
-/// A collection manages a set of Documents
-/// that we want to persist beyond the life
-/// of the service.
-pub struct Collection<T> {
+/// Outcome of decoding and parsing a single stored line, shared by every
+/// scan (`load_indexes`, `filter`, `find`, `by_uuid`, `compact`, ...) so
+/// tombstones and checksum failures are only handled in one place.
+enum RecordStatus<T> {
+    /// A blank, tombstoned slot.
+    Tombstone,
+    /// A valid, checksum-verified (or unchecked) document.
+    Ok(T),
+    /// The line isn't a tombstone but doesn't parse as `T`.
+    ParseError,
+    /// Integrity mode is on and the stored checksum didn't match. The
+    /// uuid is recovered on a best-effort basis, since the payload may
+    /// still happen to parse even though its checksum didn't match.
+    ChecksumMismatch(Option<Uuid>),
+}
+
+/// A collection manages a set of Documents that we want to persist
+/// beyond the life of the service. Storage is delegated to a [`Backend`]
+/// (defaulting to [`FileBackend`]), so the same query logic works
+/// whether records live on disk or in memory.
+pub struct Collection<T, B = FileBackend> {
     _p: PhantomData<T>,
     uuid_to_idx: HashMap<Uuid, usize>,
+    /// field name -> stringified value -> record indices, kept in sync
+    /// with insert/update/delete and rebuilt in `load_indexes`.
+    indexes: HashMap<String, HashMap<String, Vec<usize>>>,
     max_byte_length: usize,
     byte_length_increment: usize,
-    file: File,
-    fp: PathBuf,
+    backend: B,
     count: usize,
+    /// Indices of tombstoned (deleted) slots, available for `insert` to
+    /// reuse before appending at `count`.
+    free_list: Vec<usize>,
+    /// Whether records are stored with a checksum prefix, verified on
+    /// `by_uuid`/`find`/`filter` and reportable via `verify`.
+    checksums: bool,
 }
 
-impl<T> Collection<T>
+impl<T, B> Collection<T, B>
 where
     T: Document<T> + DeserializeOwned + Serialize + Debug,
+    B: Backend,
 {
-    /// Create a new collection.
-    /// Accepts an options PathBuf for writing to the filesystem.
-    /// An In-Memory DB.
-    /// bli -byte lenght
-    pub fn new(fp: PathBuf, bli: Option<usize>) -> Result<Self, String> {
-        let file = fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(fp.clone());
-        match file {
-            Ok(file) => {
-                let mut collection = Collection {
-                    _p: PhantomData,
-                    uuid_to_idx: HashMap::new(),
-                    max_byte_length: 64,
-                    byte_length_increment: bli.unwrap_or(64),
-                    file,
-                    fp,
-                    count: 0,
-                };
-                collection.load_indexes();
-                return Ok(collection);
-            }
-            Err(msg) => return Err(msg.to_string()),
+    /// Create a collection over an already-constructed backend.
+    /// `bli` (byte length increment) controls how much headroom is
+    /// added each time a record forces a resize. `checksums`, when set,
+    /// prefixes every stored record with a checksum so corruption can be
+    /// detected on read instead of silently returned; defaults to off.
+    pub fn with_backend(
+        backend: B,
+        bli: Option<usize>,
+        checksums: Option<bool>,
+    ) -> Result<Self, CollectionError> {
+        let mut collection = Collection {
+            _p: PhantomData,
+            uuid_to_idx: HashMap::new(),
+            indexes: HashMap::new(),
+            max_byte_length: 64,
+            byte_length_increment: bli.unwrap_or(64),
+            backend,
+            count: 0,
+            free_list: Vec::new(),
+            checksums: checksums.unwrap_or(false),
+        };
+        collection.load_indexes()?;
+        return Ok(collection);
+    }
+
+    /// 64-bit hash of `payload`, used as the checksum prefix in integrity mode.
+    fn checksum_of(payload: &str) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        payload.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Prefix `json` with its checksum when integrity mode is enabled,
+    /// else return it unchanged. This is the string that gets padded and
+    /// written to a slot by `write_record`.
+    fn encode_payload(&self, json: &str) -> String {
+        if self.checksums {
+            format!("{:016x}:{}", Self::checksum_of(json), json)
+        } else {
+            json.to_string()
+        }
+    }
+
+    /// Strip and verify a stored payload's checksum prefix. When
+    /// integrity mode is off, `raw` is returned unchanged. `Err` carries
+    /// whatever JSON text could still be recovered from a mismatching or
+    /// malformed prefix, so callers can best-effort recover a uuid for
+    /// error reporting.
+    fn decode_payload(&self, raw: &str) -> Result<String, Option<String>> {
+        if !self.checksums {
+            return Ok(raw.to_string());
+        }
+        if raw.len() < 17 {
+            return Err(None);
+        }
+        let (sum_hex, rest) = raw.split_at(16);
+        let rest = match rest.strip_prefix(':') {
+            Some(r) => r,
+            None => return Err(None),
+        };
+        let expected = match u64::from_str_radix(sum_hex, 16) {
+            Ok(v) => v,
+            Err(_) => return Err(None),
+        };
+        if Self::checksum_of(rest) == expected {
+            Ok(rest.to_string())
+        } else {
+            Err(Some(rest.to_string()))
         }
     }
 
-    pub fn new_arc(fp: PathBuf, bli: Option<usize>) -> Result<Arc<RwLock<Collection<T>>>, String> {
-        let collection = Collection::new(fp, bli);
-        match collection {
-            Ok(c) => {
-                return Ok(Arc::new(RwLock::new(c)));
+    /// Decode and parse a single stored line, classifying tombstones,
+    /// checksum failures and parse failures the same way everywhere.
+    fn parse_line(&self, line: &str) -> RecordStatus<T> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return RecordStatus::Tombstone;
+        }
+        match self.decode_payload(trimmed) {
+            Ok(payload) => match serde_json::from_str::<T>(&payload) {
+                Ok(doc) => RecordStatus::Ok(doc),
+                Err(_) => RecordStatus::ParseError,
+            },
+            Err(recovered) => {
+                let uuid = recovered
+                    .and_then(|payload| serde_json::from_str::<T>(&payload).ok())
+                    .map(|doc: T| doc.uuid());
+                RecordStatus::ChecksumMismatch(uuid)
             }
-            Err(msg) => return Err(msg.to_string()),
         }
     }
 
-    pub fn load_indexes(&mut self) {
-        println!("{} > File Path Provided", Utc::now());
-        let file = &self.file;
-        let reader = BufReader::new(file);
-        for (idx, line) in reader.lines().enumerate() {
-            let line = line.unwrap();
-            let document = serde_json::from_str::<T>(&line.trim());
-            if document.is_err() {
-                break;
+    /// Read the whole backend and split it into `\n`-terminated lines,
+    /// independent of the current `max_byte_length` stride.
+    fn read_all_lines(&self) -> Result<Vec<String>, CollectionError> {
+        let len = self.backend.len()?;
+        let mut buf = vec![0u8; len as usize];
+        self.backend.read_at(&mut buf, 0)?;
+        Ok(String::from_utf8_lossy(&buf)
+            .lines()
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// Write `contents` into slot `idx`, padded to `max_byte_length`.
+    fn write_record(&self, idx: usize, contents: &str) -> Result<(), CollectionError> {
+        let padded = format!("{:width$}\n", contents, width = self.max_byte_length);
+        let offset: u64 = (idx * (self.max_byte_length + 1)).try_into().unwrap();
+        self.backend.write_at(padded.as_bytes(), offset)?;
+        Ok(())
+    }
+
+    /// Read and parse the record at slot `idx`, if it's still a valid,
+    /// checksum-verified document. Used internally to fetch the previous
+    /// version of a document for index maintenance; callers that need to
+    /// surface corruption to the user go through `parse_line` directly.
+    fn read_doc_at(&self, idx: usize) -> Result<Option<T>, CollectionError> {
+        let offset: u64 = (idx * (self.max_byte_length + 1)).try_into().unwrap();
+        let line = self.backend.read_line_at(offset)?;
+        match self.parse_line(&line) {
+            RecordStatus::Ok(doc) => Ok(Some(doc)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Add `doc`'s index keys, pointing at slot `idx`.
+    fn index_insert(&mut self, idx: usize, doc: &T) {
+        for (field, value) in doc.index_keys() {
+            self.indexes
+                .entry(field)
+                .or_default()
+                .entry(value)
+                .or_default()
+                .push(idx);
+        }
+    }
+
+    /// Remove `doc`'s index keys pointing at slot `idx`.
+    fn index_remove(&mut self, idx: usize, doc: &T) {
+        for (field, value) in doc.index_keys() {
+            if let Some(values) = self.indexes.get_mut(&field) {
+                if let Some(idxs) = values.get_mut(&value) {
+                    idxs.retain(|&i| i != idx);
+                }
             }
-            let document = document.unwrap();
-            let key = document.uuid();
-            self.uuid_to_idx.insert(key, idx);
-            self.count = idx + 1
         }
     }
 
-    pub fn insert(&mut self, doc: T) -> Result<(), &str> {
-        let key = doc.uuid();
-        if self.uuid_to_idx.contains_key(&key) {
-            return Err("Primary key used");
+    /// Check `doc` doesn't clash with any existing document. When `doc`
+    /// has index keys, only the slots `indexes` says share a key/value
+    /// with it are probed, turning the check into an index lookup
+    /// instead of a full scan. Documents with no index keys have
+    /// nothing to narrow the search by, so every live record is still
+    /// checked.
+    fn check_no_clash(&self, doc: &T) -> Result<(), CollectionError> {
+        let keys = doc.index_keys();
+        if keys.is_empty() {
+            for line in self.read_all_lines()? {
+                match self.parse_line(&line) {
+                    RecordStatus::Tombstone => continue,
+                    RecordStatus::ParseError | RecordStatus::ChecksumMismatch(_) => break,
+                    RecordStatus::Ok(edoc) => {
+                        if edoc.uuid() != doc.uuid() {
+                            edoc.does_not_clash(doc).map_err(|_| CollectionError::Clash)?;
+                        }
+                    }
+                }
+            }
+            return Ok(());
         }
 
-        let reader = BufReader::new(&self.file);
-        for line in reader.lines() {
-            let line = line.unwrap();
-            // existing document
-            let edoc = serde_json::from_str::<T>(&line.trim());
-            if edoc.is_err() {
-                break;
+        let mut candidates = std::collections::HashSet::new();
+        for (field, value) in &keys {
+            if let Some(values) = self.indexes.get(field) {
+                if let Some(idxs) = values.get(value) {
+                    candidates.extend(idxs.iter().copied());
+                }
             }
-            let edoc = edoc.unwrap();
-            let ans = edoc.does_not_clash(&doc);
-            match ans {
-                Ok(()) => {}
-                Err(_) => return Err("Clash occurred"),
+        }
+        for idx in candidates {
+            if let Some(edoc) = self.read_doc_at(idx)? {
+                if edoc.uuid() != doc.uuid() {
+                    edoc.does_not_clash(doc).map_err(|_| CollectionError::Clash)?;
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Rebuild `count`/`uuid_to_idx`/`indexes`/`free_list`, and the slot
+    /// width `max_byte_length`, from a fresh scan of the backend. `insert`/`update`/`delete` call this right
+    /// after acquiring the exclusive lock, so a handle picks up whatever
+    /// another process committed since it was opened instead of deciding
+    /// where to write from its own stale, construction-time state.
+    fn rebuild_indexes(&mut self) -> Result<(), CollectionError> {
+        self.uuid_to_idx.clear();
+        self.indexes.clear();
+        self.free_list.clear();
+        self.count = 0;
+        let lines = self.read_all_lines()?;
+        // Every slot is padded to the current width, so the first line's
+        // width is the stride to read and write at.
+        if let Some(first) = lines.first().filter(|l| !l.is_empty()) {
+            self.max_byte_length = first.chars().count();
+        }
+        for (idx, line) in lines.into_iter().enumerate() {
+            match self.parse_line(&line) {
+                RecordStatus::Tombstone => {
+                    self.free_list.push(idx);
+                    self.count = idx + 1;
+                }
+                RecordStatus::Ok(document) => {
+                    let key = document.uuid();
+                    self.uuid_to_idx.insert(key, idx);
+                    self.index_insert(idx, &document);
+                    self.count = idx + 1;
+                }
+                RecordStatus::ChecksumMismatch(uuid) => {
+                    // Don't free the slot and don't add it to the
+                    // secondary indexes, since we don't have a valid
+                    // document to index, but register the uuid (if
+                    // recovered) so by_uuid reports CorruptRecord instead
+                    // of silently treating it as missing.
+                    if let Some(uuid) = uuid {
+                        self.uuid_to_idx.insert(uuid, idx);
+                    }
+                    self.count = idx + 1;
+                }
+                RecordStatus::ParseError => break,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load_indexes(&mut self) -> Result<(), CollectionError> {
+        println!("{} > File Path Provided", Utc::now());
+        self.rebuild_indexes()
+    }
 
-        let string = serde_json::to_string(&doc);
-        if string.is_err() {
-            return Err("Error turning struct into JSON");
+    pub fn insert(&mut self, doc: T) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_exclusive()?;
+        self.rebuild_indexes()?;
+
+        let key = doc.uuid();
+        if self.uuid_to_idx.contains_key(&key) {
+            return Err(CollectionError::PrimaryKeyInUse);
         }
-        let string = string.unwrap();
-        let byte_length = string.len();
+
+        self.check_no_clash(&doc)?;
+
+        let string = serde_json::to_string(&doc)?;
+        let encoded = self.encode_payload(&string);
+        let byte_length = encoded.len();
         if byte_length > self.max_byte_length {
             let div = (byte_length / self.byte_length_increment) + 1;
             self.max_byte_length = self.byte_length_increment * div;
@@ -126,227 +377,388 @@ where
                 Utc::now(),
                 self.max_byte_length
             );
-            let resize_success = self.resize_db();
-            if resize_success.is_err() {
-                return Err("Failed to resize DB");
-            }
+            self.resize_db()?;
         }
-        let padded_string = format!("{:width$}\n", string, width = self.max_byte_length);
-        let offset: u64 = (self.count * (self.max_byte_length + 1))
-            .try_into()
-            .unwrap();
-        let write_success = self.file.write_at(padded_string.as_bytes(), offset);
-        if write_success.is_err() {
-            return Err("Failed to write");
+
+        // Reuse a tombstoned slot before appending at the end.
+        let idx = self.free_list.pop().unwrap_or(self.count);
+
+        self.write_record(idx, &encoded)?;
+        self.backend.sync()?;
+        self.uuid_to_idx.insert(doc.uuid(), idx);
+        self.index_insert(idx, &doc);
+        if idx == self.count {
+            self.count += 1;
         }
-        //file.flush().unwrap();
-        self.uuid_to_idx.insert(doc.uuid(), self.count);
-        self.count += 1;
 
         return Ok(());
     }
 
-    fn resize_db(&mut self) -> Result<(), &str> {
-        fs::copy(&self.fp, "tmp.col").unwrap();
-
-        let mut tmp_path = std::env::current_dir().unwrap();
-        tmp_path.push("tmp.col");
-        let tmp_file = fs::OpenOptions::new().read(true).open(tmp_path);
-        if tmp_file.is_err() {
-            return Err("Error opening tmp file for db resize");
-        }
-        let tmp_file = tmp_file.unwrap();
-        let tmp_reader = BufReader::new(tmp_file);
-
-        let cleared = self.file.set_len(0);
-        if cleared.is_err() {
-            return Err("Failed to clear contents of DB.");
-        }
-        for (idx, line) in tmp_reader.lines().enumerate() {
-            if line.is_err() {
-                println!("Hello");
-                return Err("Line error");
-            }
-            let line = line.unwrap();
-            let repadded_string = format!("{:width$}\n", line, width = self.max_byte_length);
-            let offset: u64 = (idx * (self.max_byte_length + 1)).try_into().unwrap();
-            let write_success = self.file.write_at(repadded_string.as_bytes(), offset);
-            if write_success.is_err() {
-                return Err("Failed to write");
-            }
-        }
-        fs::remove_file("tmp.col").unwrap();
+    /// Resizes the database. Assumes an exclusive lock is already held by the caller.
+    /// `max_byte_length` must already hold the new width; existing records are
+    /// read as plain text (so the old width doesn't matter) and repadded to it.
+    /// The whole new contents are built up-front and committed via
+    /// [`Backend::replace_all`] in one atomic step, rather than truncating
+    /// then rewriting slot by slot, so a crash mid-resize can't leave the
+    /// file half-rewritten.
+    fn resize_db(&mut self) -> Result<(), CollectionError> {
+        let lines = self.read_all_lines()?;
+        let mut buf = Vec::new();
+        for line in &lines {
+            let padded = format!("{:width$}\n", line, width = self.max_byte_length);
+            buf.extend_from_slice(padded.as_bytes());
+        }
+        self.backend.replace_all(&buf)?;
         Ok(())
     }
 
     /// Update a document
-    pub fn update(&mut self, doc: T) -> Result<(), &str> {
-        // Make sure we're at the start
-        let mut reader = BufReader::new(&self.file);
-        reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-        for line in reader.lines() {
-            let line = line.unwrap();
-            // existing document
-            let edoc = serde_json::from_str::<T>(&line.trim());
-            if edoc.is_err() {
-                break;
-            }
-            let edoc = edoc.unwrap();
-            if edoc.uuid() != doc.uuid() {
-                let ans = edoc.does_not_clash(&doc);
-                match ans {
-                    Ok(()) => {}
-                    Err(_) => return Err("Clash occurred"),
-                }
-            }
+    pub fn update(&mut self, doc: T) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_exclusive()?;
+        self.rebuild_indexes()?;
+
+        self.check_no_clash(&doc)?;
+
+        let idx = self.uuid_to_idx.get(&doc.uuid());
+        if idx.is_none() {
+            return Err(CollectionError::NotFound);
         }
+        let idx = *idx.unwrap();
 
-        // Update DB.
-        let string = serde_json::to_string(&doc);
-        if string.is_err() {
-            return Err("Error turning struct into JSON");
+        // Drop the stale index entries for the previous version of this
+        // document before its record (and `max_byte_length`) changes.
+        if let Some(old_doc) = self.read_doc_at(idx)? {
+            self.index_remove(idx, &old_doc);
         }
-        let string = string.unwrap();
-        let byte_length = string.len();
+
+        // Update DB.
+        let string = serde_json::to_string(&doc)?;
+        let encoded = self.encode_payload(&string);
+        let byte_length = encoded.len();
         if byte_length > self.max_byte_length {
             let div = (byte_length / self.byte_length_increment) + 1;
             self.max_byte_length = self.byte_length_increment * div;
-            let resize_success = self.resize_db();
-            if resize_success.is_err() {
-                return Err("Failed to resize DB");
-            }
-        }
-
-        let idx = self.uuid_to_idx.get(&doc.uuid());
-        if idx.is_none() {
-            return Err("No idx found");
+            self.resize_db()?;
         }
-        let idx = idx.unwrap();
 
-        let padded_string = format!("{:width$}\n", string, width = self.max_byte_length);
         // Write right location in the file
-        let offset: u64 = (idx * (self.max_byte_length + 1)).try_into().unwrap();
-        let write_success = self.file.write_at(padded_string.as_bytes(), offset);
-        if write_success.is_err() {
-            return Err("Failed to write");
-        }
+        self.write_record(idx, &encoded)?;
+        self.backend.sync()?;
+        self.index_insert(idx, &doc);
 
         return Ok(());
     }
 
     /// Find all documents that meet the criteria.
     /// Returns a vector of immutable references.
-    pub fn filter(&self, filter_fcn: impl Fn(&T) -> bool) -> Vec<T> {
+    pub fn filter(&self, filter_fcn: impl Fn(&T) -> bool) -> Result<Vec<T>, CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+
         let mut docs: Vec<T> = vec![];
-        let mut reader = BufReader::new(&self.file);
-        reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-        for line in reader.lines() {
-            let line = line.unwrap();
-            // existing document
-            let edoc = serde_json::from_str::<T>(&line.trim());
-            if edoc.is_err() {
-                break;
-            }
-            let edoc = edoc.unwrap();
-            if filter_fcn(&edoc) {
-                docs.push(edoc);
+        for (idx, line) in self.read_all_lines()?.into_iter().enumerate() {
+            match self.parse_line(&line) {
+                RecordStatus::Tombstone => continue,
+                RecordStatus::ParseError => break,
+                RecordStatus::ChecksumMismatch(uuid) => {
+                    return Err(CollectionError::CorruptRecord {
+                        uuid: uuid.unwrap_or_else(Uuid::nil),
+                        index: idx,
+                    });
+                }
+                RecordStatus::Ok(edoc) => {
+                    if filter_fcn(&edoc) {
+                        docs.push(edoc);
+                    }
+                }
             }
         }
-        return docs;
+        return Ok(docs);
     }
 
     /// Find the first document that satisfies the criteria.
-    pub fn find(&self, find_fcn: impl Fn(&T) -> bool) -> Option<T> {
-        let mut reader = BufReader::new(&self.file);
-        reader.seek(std::io::SeekFrom::Start(0)).unwrap();
-        for line in reader.lines() {
-            let line = line.unwrap();
-            // existing document
-            let edoc = serde_json::from_str::<T>(&line.trim());
-            if edoc.is_err() {
-                break;
-            }
-            let edoc = edoc.unwrap();
-            if find_fcn(&edoc) {
-                return Some(edoc);
+    pub fn find(&self, find_fcn: impl Fn(&T) -> bool) -> Result<Option<T>, CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+
+        for (idx, line) in self.read_all_lines()?.into_iter().enumerate() {
+            match self.parse_line(&line) {
+                RecordStatus::Tombstone => continue,
+                RecordStatus::ParseError => break,
+                RecordStatus::ChecksumMismatch(uuid) => {
+                    return Err(CollectionError::CorruptRecord {
+                        uuid: uuid.unwrap_or_else(Uuid::nil),
+                        index: idx,
+                    });
+                }
+                RecordStatus::Ok(edoc) => {
+                    if find_fcn(&edoc) {
+                        return Ok(Some(edoc));
+                    }
+                }
             }
         }
-        return None;
+        return Ok(None);
     }
 
     /// Get a document by its uuid
-    pub fn by_uuid(&self, uuid: &Uuid) -> Option<T> {
+    pub fn by_uuid(&self, uuid: &Uuid) -> Result<Option<T>, CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+
         let idx = self.uuid_to_idx.get(uuid);
         if idx.is_none() {
-            return None;
+            return Ok(None);
         }
-        let idx = idx.unwrap();
-        let mut reader = BufReader::new(&self.file);
+        let idx = *idx.unwrap();
+
         let offset: u64 = (idx * (self.max_byte_length + 1)).try_into().unwrap();
-        let pos = SeekFrom::Start(offset);
-        reader.seek(pos).unwrap();
-        let mut line = String::new();
-        reader.read_line(&mut line).unwrap();
-        let edoc = serde_json::from_str::<T>(&line.trim());
-        if edoc.is_err() {
-            return None;
+        let line = self.backend.read_line_at(offset)?;
+        match self.parse_line(&line) {
+            RecordStatus::Ok(doc) => Ok(Some(doc)),
+            RecordStatus::Tombstone | RecordStatus::ParseError => Ok(None),
+            RecordStatus::ChecksumMismatch(_) => Err(CollectionError::CorruptRecord {
+                uuid: *uuid,
+                index: idx,
+            }),
         }
-        return Some(edoc.unwrap());
     }
 
-    /// Remove a document from the DB
-    pub fn delete(&mut self, uuid: &Uuid) -> Result<(), &str> {
-        let idx = self.uuid_to_idx.get(uuid);
-        if idx.is_none() {
-            return Err("No idx found");
+    /// Find all documents whose `index_keys()` contains `(field, value)`,
+    /// seeking directly to the matching records instead of scanning the file.
+    pub fn find_by(&self, field: &str, value: &str) -> Result<Vec<T>, CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+
+        let mut docs = vec![];
+        if let Some(values) = self.indexes.get(field) {
+            if let Some(idxs) = values.get(value) {
+                for &idx in idxs {
+                    if let Some(doc) = self.read_doc_at(idx)? {
+                        docs.push(doc);
+                    }
+                }
+            }
+        }
+        Ok(docs)
+    }
+
+    /// Remove a document from the DB: only its slot is written, overwritten
+    /// with a blank tombstone line and pushed onto the free list for
+    /// `insert` to reuse, rather than rewriting every surviving record.
+    /// Like `insert`/`update`, it first rescans the whole backend to
+    /// refresh the in-memory state, so the call is not O(1) overall. Call
+    /// [`Collection::compact`] to reclaim the tombstoned space.
+    pub fn delete(&mut self, uuid: &Uuid) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_exclusive()?;
+        self.rebuild_indexes()?;
+
+        let idx = match self.uuid_to_idx.remove(uuid) {
+            Some(idx) => idx,
+            None => return Err(CollectionError::NotFound),
+        };
+
+        if let Some(doc) = self.read_doc_at(idx)? {
+            self.index_remove(idx, &doc);
         }
-        let idx = idx.unwrap().clone();
 
-        // decrement all the indexes above the one being removed
-        for (_k, v) in self.uuid_to_idx.iter_mut() {
-            if *v > idx {
-                *v -= 1;
+        self.write_record(idx, "")?;
+        self.free_list.push(idx);
+
+        return Ok(());
+    }
+
+    /// Rebuild the backend densely, dropping tombstoned slots and
+    /// rebuilding `uuid_to_idx`, the secondary indexes, and the free list
+    /// in a single pass. Unlike the tombstone-based `delete`, it rewrites
+    /// every surviving record. Like `resize_db`,
+    /// the new contents are assembled up-front and committed via
+    /// [`Backend::replace_all`] in one atomic step. A checksum mismatch
+    /// aborts before anything is written or `self` is touched, rather
+    /// than silently compacting away every record past the corrupt slot.
+    pub fn compact(&mut self) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_exclusive()?;
+
+        let lines = self.read_all_lines()?;
+
+        let mut buf = Vec::new();
+        let mut new_count = 0;
+        let mut new_uuid_to_idx = HashMap::new();
+        let mut new_indexes: HashMap<String, HashMap<String, Vec<usize>>> = HashMap::new();
+        for (idx, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            let doc = match self.parse_line(line) {
+                RecordStatus::Tombstone | RecordStatus::ParseError => continue,
+                RecordStatus::ChecksumMismatch(uuid) => {
+                    return Err(CollectionError::CorruptRecord {
+                        uuid: uuid.unwrap_or_else(Uuid::nil),
+                        index: idx,
+                    });
+                }
+                RecordStatus::Ok(doc) => doc,
+            };
+            // The stored text (checksum prefix included, if any) is
+            // unchanged by moving slots, so it's rewritten verbatim.
+            let padded = format!("{:width$}\n", trimmed, width = self.max_byte_length);
+            buf.extend_from_slice(padded.as_bytes());
+            new_uuid_to_idx.insert(doc.uuid(), new_count);
+            for (field, value) in doc.index_keys() {
+                new_indexes
+                    .entry(field)
+                    .or_default()
+                    .entry(value)
+                    .or_default()
+                    .push(new_count);
             }
+            new_count += 1;
         }
+        self.backend.replace_all(&buf)?;
+        self.uuid_to_idx = new_uuid_to_idx;
+        self.indexes = new_indexes;
+        self.free_list.clear();
+        self.count = new_count;
 
-        // Remove from the map and vec.
-        self.uuid_to_idx.remove(uuid);
+        Ok(())
+    }
 
-        // Remove from the file
-        fs::copy(&self.fp, "tmp.col").unwrap();
-        let mut tmp_path = std::env::current_dir().unwrap();
-        tmp_path.push("tmp.col");
-        let tmp_file = fs::OpenOptions::new().read(true).open(tmp_path);
-        if tmp_file.is_err() {
-            return Err("Error opening tmp file for db resize");
+    /// Write every valid record to `path` as one compact (unpadded) JSON
+    /// object per line. A checksum mismatch aborts before the file is
+    /// created, rather than silently dropping every record past it.
+    pub fn dump(&self, path: PathBuf) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+
+        let mut compact_lines = Vec::new();
+        for (idx, line) in self.read_all_lines()?.into_iter().enumerate() {
+            let edoc = match self.parse_line(&line) {
+                RecordStatus::Tombstone | RecordStatus::ParseError => continue,
+                RecordStatus::ChecksumMismatch(uuid) => {
+                    return Err(CollectionError::CorruptRecord {
+                        uuid: uuid.unwrap_or_else(Uuid::nil),
+                        index: idx,
+                    });
+                }
+                RecordStatus::Ok(doc) => doc,
+            };
+            compact_lines.push(serde_json::to_string(&edoc)?);
         }
-        let tmp_file = tmp_file.unwrap();
-        let tmp_reader = BufReader::new(tmp_file);
 
-        let cleared = self.file.set_len(0);
-        if cleared.is_err() {
-            return Err("Failed to clear contents of DB.");
+        let mut writer = BufWriter::new(File::create(path)?);
+        for line in compact_lines {
+            writeln!(writer, "{}", line)?;
         }
-        let mut writer = BufWriter::new(&self.file);
-        let pos = SeekFrom::Start(0);
-        writer.seek(pos).unwrap();
-        for (lidx, line) in tmp_reader.lines().enumerate() {
-            if line.is_err() {
-                return Err("Line error");
-            }
-            if idx == lidx {
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Scan every slot and verify its checksum, when integrity mode is
+    /// enabled; a no-op collection (integrity mode off) always reports
+    /// `Ok`. Returns the indices whose checksum didn't match. A bare
+    /// `Err(Vec::new())` means the backend itself couldn't be read,
+    /// rather than that no bad slots were found.
+    pub fn verify(&self) -> Result<(), Vec<usize>> {
+        let lines = match self.read_all_lines() {
+            Ok(lines) => lines,
+            Err(_) => return Err(Vec::new()),
+        };
+        let bad: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| matches!(self.parse_line(line), RecordStatus::ChecksumMismatch(_)))
+            .map(|(idx, _)| idx)
+            .collect();
+        if bad.is_empty() {
+            Ok(())
+        } else {
+            Err(bad)
+        }
+    }
+
+    /// Produce a byte-for-byte copy of the live (padded) backend contents
+    /// at `path`, taken through a consistent read lock.
+    pub fn snapshot(&self, path: PathBuf) -> Result<(), CollectionError> {
+        let _guard = self.backend.lock_shared()?;
+        let len = self.backend.len()?;
+        let mut buf = vec![0u8; len as usize];
+        self.backend.read_at(&mut buf, 0)?;
+        fs::write(path, buf)?;
+        Ok(())
+    }
+}
+
+impl<T> Collection<T, FileBackend>
+where
+    T: Document<T> + DeserializeOwned + Serialize + Debug,
+{
+    /// Create a new collection.
+    /// Accepts an options PathBuf for writing to the filesystem.
+    /// bli -byte lenght
+    /// `lock_mode` controls whether `flock(2)` calls block until the
+    /// advisory lock is available (`LockMode::Blocking`, the default)
+    /// or fail fast (`LockMode::Try`), letting multiple processes
+    /// safely share one collection file.
+    /// `checksums`, when set, prefixes every stored record with a
+    /// checksum so `by_uuid`/`find`/`filter`/`verify` can detect
+    /// corruption instead of returning it unchecked; defaults to off.
+    /// `durable`, when set, `fsync`s the file after every `insert`/
+    /// `update` so an acknowledged write survives a crash; defaults to
+    /// off, since the extra `fsync` has a real latency cost.
+    pub fn new(
+        fp: PathBuf,
+        bli: Option<usize>,
+        lock_mode: Option<LockMode>,
+        checksums: Option<bool>,
+        durable: Option<bool>,
+    ) -> Result<Self, CollectionError> {
+        let backend = FileBackend::open(
+            &fp,
+            lock_mode.unwrap_or(LockMode::Blocking),
+            durable.unwrap_or(false),
+        )?;
+        Collection::with_backend(backend, bli, checksums)
+    }
+
+    pub fn new_arc(
+        fp: PathBuf,
+        bli: Option<usize>,
+        lock_mode: Option<LockMode>,
+        checksums: Option<bool>,
+        durable: Option<bool>,
+    ) -> Result<Arc<RwLock<Collection<T, FileBackend>>>, CollectionError> {
+        let collection = Collection::new(fp, bli, lock_mode, checksums, durable)?;
+        return Ok(Arc::new(RwLock::new(collection)));
+    }
+
+    /// Rebuild a fresh collection at `target_path` from a `.dump` file
+    /// produced by [`Collection::dump`], recomputing `max_byte_length`
+    /// from the largest record and repopulating `uuid_to_idx` as each
+    /// document is inserted.
+    pub fn restore(
+        dump_path: PathBuf,
+        target_path: PathBuf,
+        bli: Option<usize>,
+        lock_mode: Option<LockMode>,
+        checksums: Option<bool>,
+        durable: Option<bool>,
+    ) -> Result<Collection<T, FileBackend>, CollectionError> {
+        let _ = fs::remove_file(&target_path);
+        let mut collection = Collection::new(target_path, bli, lock_mode, checksums, durable)?;
+
+        let contents = fs::read_to_string(&dump_path)?;
+        let mut docs: Vec<T> = vec![];
+        let mut max_byte_length = collection.max_byte_length;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
                 continue;
             }
-            let line = line.unwrap();
-            let repadded_string = format!("{:width$}\n", line, width = self.max_byte_length);
-            let write_success = writer.write(repadded_string.as_bytes());
-            if write_success.is_err() {
-                return Err("Failed to write");
-            }
-            writer.flush().unwrap();
+            max_byte_length = max_byte_length.max(line.len());
+            docs.push(serde_json::from_str::<T>(line)?);
         }
-        fs::remove_file("tmp.col").unwrap();
-        return Ok(());
+
+        let div = (max_byte_length / collection.byte_length_increment) + 1;
+        collection.max_byte_length = collection.byte_length_increment * div;
+
+        for doc in docs {
+            collection.insert(doc)?;
+        }
+
+        Ok(collection)
     }
 }
 
@@ -373,6 +785,10 @@ mod test {
             }
             return Ok(());
         }
+
+        fn index_keys(&self) -> Vec<(String, String)> {
+            vec![("name".to_string(), self.name.clone())]
+        }
     }
 
     impl User {
@@ -389,27 +805,27 @@ mod test {
         let mut fp = std::env::current_dir().unwrap();
         fp.push("user.col");
         let _ = fs::remove_file(fp.clone());
-        let mut c = Collection::<User>::new(fp, None).unwrap();
+        let mut c = Collection::<User>::new(fp, None, None, None, None).unwrap();
 
         let user_bob = User::new("bob".to_string());
         let mut user_bob_cloned = user_bob.clone();
-        let res: Result<(), &str> = c.insert(user_bob);
+        let res = c.insert(user_bob);
         if res.is_err() {
-            println!("{:?}", res.unwrap())
+            println!("{:?}", res.as_ref().unwrap_err())
         }
         assert_eq!(res.is_ok(), true);
 
         let user_resize_db_long_name = User::new("user_resize_db_long_name".to_string());
-        let res: Result<(), &str> = c.insert(user_resize_db_long_name);
+        let res = c.insert(user_resize_db_long_name);
         if res.is_err() {
-            println!("{:?}", res.unwrap())
+            println!("{:?}", res.as_ref().unwrap_err())
         }
         assert_eq!(res.is_ok(), true);
 
         user_bob_cloned.name = "Trevor".to_string();
         let res = c.update(user_bob_cloned);
         if res.is_err() {
-            println!("{:?}", res.unwrap())
+            println!("{:?}", res.as_ref().unwrap_err())
         }
         assert_eq!(res.is_ok(), true);
 
@@ -422,7 +838,7 @@ mod test {
         let uuid = user.uuid.clone();
         let res = c.insert(user);
         assert_eq!(res.is_ok(), true);
-        let get_user = c.by_uuid(&uuid);
+        let get_user = c.by_uuid(&uuid).unwrap();
         if get_user.is_some() {
             println!("{:?}", get_user.unwrap());
         }
@@ -430,9 +846,174 @@ mod test {
         let del = c.delete(&uuid_bill);
         assert_eq!(del.is_ok(), true);
 
-        let get_user = c.by_uuid(&uuid);
+        let get_user = c.by_uuid(&uuid).unwrap();
         if get_user.is_some() {
             println!("{:?}", get_user.unwrap());
         }
     }
+
+    #[test]
+    fn test_try_lock_mode() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_lock.col");
+        let _ = fs::remove_file(fp.clone());
+
+        let mut c = Collection::<User>::new(fp, None, Some(LockMode::Try), None, None).unwrap();
+        let res = c.insert(User::new("alice".to_string()));
+        assert_eq!(res.is_ok(), true);
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_dump_source.col");
+        let _ = fs::remove_file(fp.clone());
+        let mut c = Collection::<User>::new(fp, None, None, None, None).unwrap();
+
+        let user = User::new("user_with_a_fairly_long_name_for_resize".to_string());
+        let uuid = user.uuid;
+        c.insert(user).unwrap();
+        c.insert(User::new("frank".to_string())).unwrap();
+
+        let mut dump_fp = std::env::current_dir().unwrap();
+        dump_fp.push("user.dump");
+        c.dump(dump_fp.clone()).unwrap();
+
+        let mut snapshot_fp = std::env::current_dir().unwrap();
+        snapshot_fp.push("user_snapshot.col");
+        c.snapshot(snapshot_fp).unwrap();
+
+        let mut restored_fp = std::env::current_dir().unwrap();
+        restored_fp.push("user_restored.col");
+        let restored = Collection::<User>::restore(dump_fp, restored_fp, None, None, None, None).unwrap();
+
+        let found = restored.by_uuid(&uuid).unwrap();
+        assert_eq!(found.is_some(), true);
+    }
+
+    #[test]
+    fn test_in_memory_backend() {
+        let mut c: Collection<User, InMemoryBackend> =
+            Collection::with_backend(InMemoryBackend::new(), None, None).unwrap();
+
+        let user = User::new("gina".to_string());
+        let uuid = user.uuid;
+        c.insert(user).unwrap();
+
+        let found = c.by_uuid(&uuid).unwrap();
+        assert_eq!(found.is_some(), true);
+
+        c.delete(&uuid).unwrap();
+        let found = c.by_uuid(&uuid).unwrap();
+        assert_eq!(found.is_some(), false);
+    }
+
+    #[test]
+    fn test_find_by_secondary_index() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_find_by.col");
+        let _ = fs::remove_file(fp.clone());
+        let mut c = Collection::<User>::new(fp, None, None, None, None).unwrap();
+
+        c.insert(User::new("harriet".to_string())).unwrap();
+        let second = User::new("irene".to_string());
+        let second_uuid = second.uuid;
+        c.insert(second).unwrap();
+
+        let found = c.find_by("name", "irene").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].uuid, second_uuid);
+
+        // Update should move the index entry, not duplicate it.
+        let mut renamed = c.by_uuid(&second_uuid).unwrap().unwrap();
+        renamed.name = "renamed".to_string();
+        c.update(renamed).unwrap();
+        assert_eq!(c.find_by("name", "irene").unwrap().len(), 0);
+        assert_eq!(c.find_by("name", "renamed").unwrap().len(), 1);
+
+        // Delete should drop the index entry entirely.
+        c.delete(&second_uuid).unwrap();
+        assert_eq!(c.find_by("name", "renamed").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_delete_reuses_tombstoned_slot_and_compact_drops_it() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_tombstone.col");
+        let _ = fs::remove_file(fp.clone());
+        let mut c = Collection::<User>::new(fp, None, None, None, None).unwrap();
+
+        let first = User::new("kayla".to_string());
+        let first_uuid = first.uuid;
+        c.insert(first).unwrap();
+        c.insert(User::new("liam".to_string())).unwrap();
+
+        c.delete(&first_uuid).unwrap();
+        assert_eq!(c.by_uuid(&first_uuid).unwrap().is_some(), false);
+
+        // The tombstoned slot should be reused rather than the file growing.
+        let reuser = User::new("mara".to_string());
+        let reuser_uuid = reuser.uuid;
+        c.insert(reuser).unwrap();
+        assert_eq!(c.count, 2);
+        assert_eq!(c.by_uuid(&reuser_uuid).unwrap().is_some(), true);
+
+        // Delete again and compact: tombstones should be dropped entirely.
+        c.delete(&reuser_uuid).unwrap();
+        c.compact().unwrap();
+        assert_eq!(c.count, 1);
+        assert_eq!(c.free_list.len(), 0);
+    }
+
+    #[test]
+    fn test_checksum_mode_detects_corruption() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_checksum.col");
+        let _ = fs::remove_file(fp.clone());
+        let mut c = Collection::<User>::new(fp, None, None, Some(true), None).unwrap();
+
+        let user = User::new("noah".to_string());
+        let uuid = user.uuid;
+        c.insert(user).unwrap();
+        assert_eq!(c.verify(), Ok(()));
+
+        // Corrupt the payload without touching the checksum prefix.
+        let idx = *c.uuid_to_idx.get(&uuid).unwrap();
+        let offset: u64 = (idx * (c.max_byte_length + 1)).try_into().unwrap();
+        let line = c.backend.read_line_at(offset).unwrap();
+        let mut corrupted = line.into_bytes();
+        let flip_at = corrupted.iter().rposition(|&b| b == b'n').unwrap();
+        corrupted[flip_at] = b'x';
+        c.backend.write_at(&corrupted, offset).unwrap();
+
+        assert_eq!(c.verify(), Err(vec![idx]));
+        match c.by_uuid(&uuid) {
+            Err(CollectionError::CorruptRecord {
+                uuid: bad_uuid,
+                index,
+            }) => {
+                assert_eq!(bad_uuid, uuid);
+                assert_eq!(index, idx);
+            }
+            other => panic!("expected CorruptRecord, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_durable_mode_and_resize_survive_reopen() {
+        let mut fp = std::env::current_dir().unwrap();
+        fp.push("user_durable.col");
+        let _ = fs::remove_file(fp.clone());
+        let mut c = Collection::<User>::new(fp.clone(), Some(16), None, None, Some(true)).unwrap();
+
+        c.insert(User::new("opal".to_string())).unwrap();
+        // Forces `resize_db`'s atomic replace-all path.
+        c.insert(User::new("a_rather_long_name_to_force_a_resize".to_string()))
+            .unwrap();
+
+        // Reopening from the same path should see exactly what was
+        // written, proving the atomic rename left a complete file behind.
+        let reopened = Collection::<User>::new(fp, Some(16), None, None, Some(true)).unwrap();
+        assert_eq!(reopened.count, 2);
+    }
 }